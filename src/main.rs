@@ -4,6 +4,7 @@ use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 extern crate failure;
 extern crate failure_derive;
 use failure::{err_msg, Error, Fail};
+use futures::stream::{self, StreamExt};
 use reqwest;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -11,9 +12,12 @@ use serde_yaml;
 use std::cmp::max;
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::{self, Write};
 use std::ops::Deref;
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use url::form_urlencoded::byte_serialize;
+use warp::Filter;
 
 #[derive(Fail, Debug)]
 enum InsightsError {
@@ -43,114 +47,360 @@ struct QueryResults {
     raw: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    timestamp: u64,
+    raw: String,
+}
+
+// An on-disk result cache, keyed by (account_id, url, normalized nrql), so
+// repeated identical queries (the `complete`/`attrs` shell-completion helpers
+// in particular) can skip the HTTP round-trip entirely.
+// Cheaply `Clone` (it wraps a `sled::Db` handle, which is itself a cheap
+// handle around shared state), so a single opened cache can be shared by
+// every `Connection` built from a run, rather than re-opening — and
+// re-acquiring sled's exclusive directory lock on — `~/.insights-cache/`
+// for every get/set.
+#[derive(Clone)]
+struct QueryCache {
+    db: sled::Db,
+}
+
+impl QueryCache {
+    fn open() -> Result<QueryCache, Error> {
+        let home_dir =
+            dirs::home_dir().ok_or(err_msg("Unable to find home directory for cache!"))?;
+        let cache_dir = format!("{}/.insights-cache/", home_dir.display());
+        Ok(QueryCache {
+            db: sled::open(cache_dir)?,
+        })
+    }
+
+    fn get(&self, key: &str, ttl: Duration) -> Result<Option<String>, Error> {
+        let bytes = match self.db.get(key.as_bytes())? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let entry: CacheEntry = serde_json::from_slice(&bytes)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if now.saturating_sub(entry.timestamp) < ttl.as_secs() {
+            return Ok(Some(entry.raw));
+        }
+        Ok(None)
+    }
+
+    fn set(&self, key: &str, raw: &str) -> Result<(), Error> {
+        let entry = CacheEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            raw: raw.to_owned(),
+        };
+        self.db.insert(key.as_bytes(), serde_json::to_vec(&entry)?)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), Error> {
+        self.db.clear()?;
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+// Collapses incidental whitespace differences so equivalent queries share a
+// cache key, without lowercasing: NRQL keywords are case-insensitive but
+// string literals aren't, and lowercasing the whole query would collapse
+// `... = 'Prod'` and `... = 'prod'` into the same key, silently serving one
+// query's cached result for the other.
+fn normalize_nrql(nrql: &str) -> String {
+    nrql.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn cache_key(account_id: &str, url: &str, nrql: &str) -> String {
+    format!("{}:{}:{}", account_id, url, normalize_nrql(nrql))
+}
+
+// Parses durations like "5m", "1h", "30s", or a bare number of seconds.
+fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let s = s.trim();
+    let split_at = s
+        .find(|c: char| c.is_alphabetic())
+        .unwrap_or_else(|| s.len());
+    let (amount, suffix) = s.split_at(split_at);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| err_msg(format!("Invalid duration: {}", s)))?;
+    let secs = match suffix {
+        "" | "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 60 * 60,
+        "d" => amount * 60 * 60 * 24,
+        _ => return Err(err_msg(format!("Invalid duration suffix: {}", suffix))),
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+// Extracts a JSON value's raw text, as opposed to serde_json's JSON-escaped
+// `to_string()` (which would turn an embedded newline into the two chars
+// `\n` and an embedded quote into `\"`, hiding them from `csv_field`).
+fn scalar_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// Quotes a CSV field per RFC 4180 when it contains a comma, double-quote,
+// or newline, escaping embedded quotes by doubling them.
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[derive(Clone)]
 struct Connection {
     account_id: String,
     api_key: String,
     url: String,
+    cache_ttl: Option<Duration>,
+    cache: Option<QueryCache>,
 }
 
 impl Connection {
+    // Credentials are resolved in precedence order: CLI flags, then
+    // environment variables (INSIGHTS_ACCOUNT_ID, INSIGHTS_API_KEY,
+    // INSIGHTS_URL, INSIGHTS_ACCOUNT), then the ~/.insights.yaml config
+    // file. Each layer that supplies account_id/api_key at all must supply
+    // both, keeping CI/container setups from running with a half-configured
+    // connection.
     fn from_args(matches: &ArgMatches) -> Result<Connection, Error> {
         let default_url = "https://insights-api.newrelic.com/".to_string();
-        let account_id = matches.value_of("account_id");
-        let api_key = matches.value_of("api_key");
-        let url = matches.value_of("url");
-        if account_id == None || api_key == None {
-            if account_id != None || api_key != None {
+        let cache_ttl = Connection::resolve_cache_ttl(matches)?;
+        let cache = Connection::resolve_cache(matches, cache_ttl)?;
+
+        let cli_account_id = matches.value_of("account_id");
+        let cli_api_key = matches.value_of("api_key");
+        if cli_account_id.is_some() || cli_api_key.is_some() {
+            if cli_account_id.is_none() || cli_api_key.is_none() {
                 return Err(err_msg(
                     "Either pass in both account_id and api_key or pull in both from the config.",
                 ));
             }
-            let home_dir = dirs::home_dir().ok_or(err_msg(
-                "Unable to find home directory for config. Must provide account_id and api_key!",
-            ))?;
-            let config_path = format!("{}/.insights.yaml", home_dir.display());
-            if !Path::new(config_path.as_str()).exists() {
-                return Err(err_msg(format!(
-                    "{} does not exist. Must provide account_id and api_key!",
-                    config_path
-                )));
+            return Ok(Connection {
+                account_id: cli_account_id.unwrap().to_owned(),
+                api_key: cli_api_key.unwrap().to_owned(),
+                url: Connection::resolve_url(matches, None, &default_url),
+                cache_ttl,
+                cache,
+            });
+        }
+
+        let env_account_id = std::env::var("INSIGHTS_ACCOUNT_ID").ok();
+        let env_api_key = std::env::var("INSIGHTS_API_KEY").ok();
+        if env_account_id.is_some() || env_api_key.is_some() {
+            if env_account_id.is_none() || env_api_key.is_none() {
+                return Err(err_msg(
+                    "Either set both INSIGHTS_ACCOUNT_ID and INSIGHTS_API_KEY or pull in both from the config.",
+                ));
             }
-            let file = File::open(config_path)?;
-            let config: Config = serde_yaml::from_reader(file)?;
-            let account = matches
-                .value_of("account")
-                .or(config.default.as_ref().map(Deref::deref))
-                .ok_or(err_msg("No account specified!"))?;
-            let accounts = &(config.accounts).unwrap();
-            let account_config = accounts.get(account).ok_or(err_msg(format!(
-                "Unable to find account config for {}!",
-                &account
-            )))?;
-            let url = account_config
-                .url
-                .as_ref()
-                .unwrap_or(&default_url.to_owned())
-                .to_string();
             return Ok(Connection {
-                account_id: account_config.account_id.to_string(),
-                api_key: account_config.api_key.to_string(),
-                url: url.to_string(),
+                account_id: env_account_id.unwrap(),
+                api_key: env_api_key.unwrap(),
+                url: Connection::resolve_url(matches, None, &default_url),
+                cache_ttl,
+                cache,
             });
         }
 
+        let config = load_config()?;
+        let env_account = std::env::var("INSIGHTS_ACCOUNT").ok();
+        let account = matches
+            .value_of("account")
+            .or(env_account.as_deref())
+            .or(config.default.as_ref().map(Deref::deref))
+            .ok_or(err_msg("No account specified!"))?;
+        Connection::for_account(matches, &config, account, cache_ttl, cache)
+    }
+
+    // Builds a connection for a specific account key out of an already
+    // loaded `Config`, used both by the single-account path above and by
+    // `--all-accounts` fan-out, which needs one `Connection` per account.
+    // `cache` is an already-opened handle (see `resolve_cache`) shared
+    // across every account, so fan-out doesn't reopen sled's on-disk cache
+    // per account either.
+    fn for_account(
+        matches: &ArgMatches,
+        config: &Config,
+        account: &str,
+        cache_ttl: Option<Duration>,
+        cache: Option<QueryCache>,
+    ) -> Result<Connection, Error> {
+        let default_url = "https://insights-api.newrelic.com/".to_string();
+        let accounts = config
+            .accounts
+            .as_ref()
+            .ok_or(err_msg("No accounts configured!"))?;
+        let account_config = accounts.get(account).ok_or(err_msg(format!(
+            "Unable to find account config for {}!",
+            &account
+        )))?;
         Ok(Connection {
-            account_id: account_id.unwrap().to_owned(),
-            api_key: api_key.unwrap().to_owned(),
-            url: url.unwrap_or(default_url.as_str()).to_string(),
+            account_id: account_config.account_id.to_string(),
+            api_key: account_config.api_key.to_string(),
+            url: Connection::resolve_url(matches, account_config.url.as_deref(), &default_url),
+            cache_ttl,
+            cache,
         })
     }
 
-    fn run_query(&self, query: &str) -> Result<QueryResults, Error> {
+    fn resolve_cache_ttl(matches: &ArgMatches) -> Result<Option<Duration>, Error> {
+        matches
+            .value_of("cache_ttl")
+            .map(parse_duration)
+            .transpose()
+    }
+
+    // Opens the on-disk cache once, up front, so `run_query` can reuse the
+    // same `QueryCache` (and thus the same `sled::Db` handle) for both its
+    // get and its set instead of re-opening — and re-acquiring the
+    // exclusive directory lock on — `~/.insights-cache/` on every call.
+    // Returns None when there's nothing to cache (no TTL) or caching was
+    // explicitly disabled.
+    fn resolve_cache(
+        matches: &ArgMatches,
+        cache_ttl: Option<Duration>,
+    ) -> Result<Option<QueryCache>, Error> {
+        if matches.is_present("no_cache") || cache_ttl.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(QueryCache::open()?))
+    }
+
+    // Resolves the URL with the same CLI flag > env var precedence used for
+    // credentials, falling back to `config_url` (the account's configured
+    // url, when resolving from the config file) and finally `default_url`.
+    fn resolve_url(matches: &ArgMatches, config_url: Option<&str>, default_url: &str) -> String {
+        matches
+            .value_of("url")
+            .map(str::to_owned)
+            .or_else(|| std::env::var("INSIGHTS_URL").ok())
+            .or_else(|| config_url.map(str::to_owned))
+            .unwrap_or_else(|| default_url.to_owned())
+    }
+
+    // Path to the YAML config file consulted by `from_args` when credentials
+    // aren't passed as flags or environment variables. Returns None when the
+    // connection was built from flags or environment variables, since there's
+    // nothing to watch for changes.
+    fn config_path(matches: &ArgMatches) -> Option<String> {
+        if matches.value_of("account_id").is_some() || matches.value_of("api_key").is_some() {
+            return None;
+        }
+        if std::env::var("INSIGHTS_ACCOUNT_ID").is_ok() || std::env::var("INSIGHTS_API_KEY").is_ok()
+        {
+            return None;
+        }
+        let home_dir = dirs::home_dir()?;
+        Some(format!("{}/.insights.yaml", home_dir.display()))
+    }
+
+    async fn run_query(&self, query: &str) -> Result<QueryResults, Error> {
+        let key = cache_key(&self.account_id, &self.url, query);
+        if let (Some(cache), Some(ttl)) = (&self.cache, self.cache_ttl) {
+            if let Some(raw) = cache.get(&key, ttl)? {
+                return Ok(QueryResults { raw });
+            }
+        }
+
         let encoded_nrql: String = byte_serialize(query.as_bytes()).collect();
         let client = reqwest::Client::new();
         let url = format!(
             "{}v1/accounts/{}/query?nrql={}",
             &self.url, &self.account_id, encoded_nrql
         );
-        println!("{}", query);
-        let mut body = client
+        eprintln!("{}", query);
+        let body = client
             .get(url.as_str())
             .header("Accept", "application/json")
             .header("X-Query-Key", &self.api_key)
-            .send()?;
-        Ok(QueryResults { raw: body.text()? })
+            .send()
+            .await?;
+        let raw = body.text().await?;
+
+        if let Some(cache) = &self.cache {
+            cache.set(&key, &raw)?;
+        }
+
+        Ok(QueryResults { raw })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Format {
     Raw,
     JSON,
     CSV,
     Table,
+    Template(String),
 }
 
 impl Format {
-    fn from_args(matches: &ArgMatches) -> Format {
+    fn from_args(matches: &ArgMatches) -> Result<Format, Error> {
         if matches.is_present("json") {
-            return Format::JSON;
+            return Ok(Format::JSON);
         }
         if matches.is_present("csv") {
-            return Format::CSV;
+            return Ok(Format::CSV);
         }
         if matches.is_present("raw") {
-            return Format::Raw;
+            return Ok(Format::Raw);
+        }
+        if let Some(path) = matches.value_of("template") {
+            return Ok(Format::Template(std::fs::read_to_string(path)?));
+        }
+        if let Some(template) = matches.value_of("template_inline") {
+            return Ok(Format::Template(template.to_owned()));
         }
         if matches.is_present("table") {
-            return Format::Table;
+            return Ok(Format::Table);
+        }
+        Ok(Format::Table)
+    }
+
+    fn from_query_param(format: Option<&str>) -> Format {
+        match format {
+            Some("json") => Format::JSON,
+            Some("csv") => Format::CSV,
+            Some("raw") => Format::Raw,
+            _ => Format::Table,
         }
-        return Format::Table;
     }
 }
 
 impl QueryResults {
+    // Results are an object with a key of events or eventTypes.
+    // Pull the value of whatever is there.
+    fn values(&self) -> Result<Vec<Value>, Error> {
+        let parsed = serde_json::from_str::<Results>(&self.raw)?;
+        let props = &parsed.results[0]
+            .as_object()
+            .ok_or(err_msg("Unexpected response shape from Insights!"))?;
+        let key = props.keys().next().ok_or(err_msg("Empty result set!"))?;
+        Ok(props[key]
+            .as_array()
+            .ok_or(err_msg("Unexpected response shape from Insights!"))?
+            .to_owned())
+    }
+
     fn print(&self, format: Format) -> Result<(), Error> {
         match format {
             Format::Table => self.print_table(),
             Format::JSON => self.print_json(),
             Format::Raw => self.print_raw(),
-            Format::CSV => Err(err_msg("Unimplemented Output Format: CSV")),
+            Format::CSV => self.print_csv(),
+            Format::Template(template) => self.print_template(&template),
         }
     }
 
@@ -164,21 +414,82 @@ impl QueryResults {
     }
 
     fn print_json(&self) -> Result<(), Error> {
-        let parsed = serde_json::from_str::<Results>(&self.raw).unwrap();
-        // Results are an object with a key of events or eventTypes.
-        // Pull the value of whatever is there.
-        let props = &parsed.results[0].as_object().unwrap();
-        let value = props[props.keys().next().unwrap()].as_array().unwrap();
-        println!("{}", serde_json::to_string_pretty(value)?);
+        let value = self.values()?;
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        Ok(())
+    }
+
+    // Renders each result row through a user-supplied Handlebars template,
+    // e.g. `{{timestamp}} {{appName}}: {{duration}}ms`, for custom report
+    // lines or Markdown tables without post-processing with `jq`.
+    fn print_template(&self, template: &str) -> Result<(), Error> {
+        print!("{}", self.render_template(template)?);
+        Ok(())
+    }
+
+    fn render_template(&self, template: &str) -> Result<String, Error> {
+        let mut handlebars = handlebars::Handlebars::new();
+        // Plain report lines, Markdown tables, and alert text shouldn't have
+        // their values HTML-escaped (the default escape fn would mangle
+        // `&`/`<`/`"` in e.g. an NRQL string value or a Markdown table cell).
+        handlebars.register_escape_fn(handlebars::no_escape);
+        let mut out = String::new();
+        for row in self.values()? {
+            out.push_str(&handlebars.render_template(template, &row)?);
+            out.push_str("\n");
+        }
+        Ok(out)
+    }
+
+    fn print_csv(&self) -> Result<(), Error> {
+        print!("{}", self.render_csv()?);
         Ok(())
     }
 
+    // Reuses the column-discovery approach from `print_table` (the first
+    // object's keys become the header), but emits RFC 4180-compliant rows:
+    // fields containing a comma, double-quote, or newline are quoted, with
+    // embedded quotes escaped by doubling. The header key order is fixed up
+    // front and every row looks up its values by that order, so rows with a
+    // missing key emit an empty field instead of shifting columns.
+    fn render_csv(&self) -> Result<String, Error> {
+        let mut out = String::new();
+        let mut header: Option<Vec<String>> = None;
+        for v in self.values()? {
+            match v {
+                Value::String(string_value) => {
+                    out.push_str(&csv_field(&string_value));
+                    out.push_str("\n");
+                }
+                Value::Object(obj_value) => {
+                    let first = header.is_none();
+                    let keys = header.get_or_insert_with(|| obj_value.keys().cloned().collect());
+                    if first {
+                        out.push_str(
+                            &keys
+                                .iter()
+                                .map(|k| csv_field(k))
+                                .collect::<Vec<String>>()
+                                .join(","),
+                        );
+                        out.push_str("\n");
+                    }
+                    let row = keys
+                        .iter()
+                        .map(|k| csv_field(&obj_value.get(k).map(scalar_string).unwrap_or_default()))
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    out.push_str(&row);
+                    out.push_str("\n");
+                }
+                _ => out.push_str("Unexpected type in result!\n"),
+            }
+        }
+        Ok(out)
+    }
+
     fn print_table(&self) -> Result<(), Error> {
-        let parsed = serde_json::from_str::<Results>(&self.raw).unwrap();
-        // Results are an object with a key of events or eventTypes.
-        // Pull the value of whatever is there.
-        let props = &parsed.results[0].as_object().unwrap();
-        let value = props[props.keys().next().unwrap()].as_array().unwrap();
+        let value = self.values()?;
         let mut first: bool = true;
         // Note: This does not properly handle unicode characters!
         let mut widths = Vec::<usize>::new();
@@ -224,18 +535,351 @@ impl QueryResults {
         }
         Ok(())
     }
+
+    // Renders the result the same way `print` does, but returns the output
+    // instead of writing it to stdout. Used by the `serve` subcommand, where
+    // the formatted result becomes the HTTP response body rather than
+    // terminal output.
+    fn render(&self, format: Format) -> Result<String, Error> {
+        match format {
+            Format::Raw => Ok(serde_json::to_string_pretty(&serde_json::from_str::<Value>(
+                &self.raw,
+            )?)?),
+            Format::JSON => Ok(serde_json::to_string_pretty(&self.values()?)?),
+            Format::CSV => self.render_csv(),
+            Format::Template(template) => self.render_template(&template),
+            Format::Table => {
+                let mut out = String::new();
+                let mut first: bool = true;
+                let mut widths = Vec::<usize>::new();
+                let mut rows = Vec::<Vec<String>>::new();
+                for v in self.values()? {
+                    match v {
+                        Value::String(string_value) => out.push_str(&format!("{}\n", string_value)),
+                        Value::Object(obj_value) => {
+                            if first {
+                                let mut row = Vec::<String>::new();
+                                for key in obj_value.keys() {
+                                    widths.push(key.len());
+                                    row.push(key.to_owned());
+                                }
+                                first = false;
+                                rows.push(row);
+                            }
+                            let row = obj_value
+                                .keys()
+                                .map(|k| {
+                                    obj_value[k]
+                                        .to_string()
+                                        .trim_matches::<&[char]>(&['"'])
+                                        .to_owned()
+                                })
+                                .collect::<Vec<String>>();
+                            widths = row
+                                .iter()
+                                .map(|c| c.len())
+                                .zip(widths)
+                                .map(|(cw, mw)| max(cw, mw))
+                                .collect();
+                            rows.push(row);
+                        }
+                        _ => out.push_str("Unexpected type in result!\n"),
+                    }
+                }
+                for row in rows {
+                    for (column, width) in row.iter().zip(&widths) {
+                        out.push_str(&format!("{:<width$} ", column, width = width));
+                    }
+                    out.push_str("\n");
+                }
+                Ok(out)
+            }
+        }
+    }
 }
 
-fn process_matches(matches: ArgMatches) -> Result<(), Error> {
+// A thin JSON error envelope returned to callers of `serve` instead of the
+// eprintln!+exit(1) used by the CLI path.
+#[derive(Debug, Serialize)]
+struct ErrorEnvelope {
+    error: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryParams {
+    nrql: String,
+    format: Option<String>,
+}
+
+async fn serve(connection: Connection, matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let port: u16 = matches
+        .value_of("port")
+        .unwrap_or("8080")
+        .parse()
+        .map_err(|_| err_msg("Invalid --port value!"))?;
+
+    let query_route = warp::path("query")
+        .and(warp::get())
+        .and(warp::query::<QueryParams>())
+        .and_then(move |params: QueryParams| {
+            let connection = connection.clone();
+            async move { Ok::<_, std::convert::Infallible>(handle_query(&connection, params).await) }
+        });
+
+    println!("Serving NRQL queries on http://127.0.0.1:{}/query", port);
+    warp::serve(query_route).run(([127, 0, 0, 1], port)).await;
+    Ok(())
+}
+
+async fn handle_query(connection: &Connection, params: QueryParams) -> impl warp::Reply {
+    let format = Format::from_query_param(params.format.as_ref().map(String::as_str));
+    let result = match connection.run_query(&params.nrql).await {
+        Ok(results) => results.render(format),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(body) => warp::reply::with_status(body, warp::http::StatusCode::OK),
+        Err(e) => warp::reply::with_status(
+            serde_json::to_string(&ErrorEnvelope {
+                error: e.to_string(),
+            })
+            .unwrap_or_else(|_| "{\"error\":\"unknown error\"}".to_string()),
+            warp::http::StatusCode::BAD_REQUEST,
+        ),
+    }
+}
+
+// Runs every query in `queries` concurrently (bounded by `concurrency`),
+// tagging each result with its original index so output can be printed in
+// input order even though the requests complete out of order.
+async fn run_batch(
+    connection: &Connection,
+    queries: Vec<String>,
+    concurrency: usize,
+) -> Vec<(usize, String, Result<QueryResults, Error>)> {
+    let mut results: Vec<(usize, String, Result<QueryResults, Error>)> = stream::iter(queries.into_iter().enumerate())
+        .map(|(index, query)| async move {
+            let result = connection.run_query(&query).await;
+            (index, query, result)
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    results.sort_by_key(|(index, _, _)| *index);
+    results
+}
+
+fn read_batch_file(path: &str) -> Result<Vec<String>, Error> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+// Re-runs `nrql` on an interval, clearing the screen and reprinting a table
+// each tick, giving a live `top`-style view of a query. Also watches the
+// config file for changes so a rotated API key or account switch takes
+// effect without restarting.
+async fn follow_query(
+    mut connection: Connection,
+    matches: &ArgMatches<'_>,
+    nrql: &str,
+    run: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let interval: u64 = run
+        .value_of("interval")
+        .unwrap_or("5")
+        .parse()
+        .map_err(|_| err_msg("Invalid --interval value!"))?;
+    let count: Option<u64> = run
+        .value_of("count")
+        .map(|c| c.parse())
+        .transpose()
+        .map_err(|_| err_msg("Invalid --count value!"))?;
+
+    let config_path = Connection::config_path(matches);
+    let mut last_mtime = config_path.as_ref().and_then(|p| config_mtime(p));
+
+    let mut iterations: u64 = 0;
+    loop {
+        if let Some(path) = &config_path {
+            let mtime = config_mtime(path);
+            if mtime.is_some() && mtime != last_mtime {
+                connection = Connection::from_args(matches)?;
+                last_mtime = mtime;
+            }
+        }
+
+        print!("\x1B[2J\x1B[H");
+        connection.run_query(nrql).await?.print_table()?;
+        io::stdout().flush()?;
+
+        iterations += 1;
+        if let Some(n) = count {
+            if iterations >= n {
+                break;
+            }
+        }
+        tokio::time::delay_for(Duration::from_secs(interval)).await;
+    }
+    Ok(())
+}
+
+fn config_mtime(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn load_config() -> Result<Config, Error> {
+    let home_dir = dirs::home_dir().ok_or(err_msg(
+        "Unable to find home directory for config. Must provide account_id and api_key!",
+    ))?;
+    let config_path = format!("{}/.insights.yaml", home_dir.display());
+    if !Path::new(config_path.as_str()).exists() {
+        return Err(err_msg(format!(
+            "{} does not exist. Must provide account_id and api_key!",
+            config_path
+        )));
+    }
+    let file = File::open(config_path)?;
+    Ok(serde_yaml::from_reader(file)?)
+}
+
+// Resolves the set of account keys to fan a query out across: every
+// configured account under `--all-accounts`, or an explicit comma-separated
+// list passed to `-a`/`--account`. Returns None for the normal single
+// account path.
+fn account_keys_for_fanout(
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<Option<Vec<String>>, Error> {
+    if matches.is_present("all_accounts") {
+        let accounts = config
+            .accounts
+            .as_ref()
+            .ok_or(err_msg("No accounts configured!"))?;
+        return Ok(Some(accounts.keys().cloned().collect()));
+    }
+    if let Some(account) = matches.value_of("account") {
+        if account.contains(',') {
+            return Ok(Some(
+                account.split(',').map(str::trim).map(str::to_owned).collect(),
+            ));
+        }
+    }
+    Ok(None)
+}
+
+// Runs `nrql` against every account in `account_keys`, tagging each result
+// row with its account key before merging into a single result set that
+// the chosen `Format` is applied to once.
+async fn run_query_across_accounts(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    account_keys: &[String],
+    nrql: &str,
+) -> Result<QueryResults, Error> {
+    let cache_ttl = Connection::resolve_cache_ttl(matches)?;
+    let cache = Connection::resolve_cache(matches, cache_ttl)?;
+    let mut merged = Vec::new();
+    for account in account_keys {
+        let connection =
+            Connection::for_account(matches, config, account, cache_ttl, cache.clone())?;
+        for row in connection.run_query(nrql).await?.values()? {
+            let tagged = match row {
+                Value::Object(mut obj) => {
+                    obj.insert("account".to_string(), Value::String(account.clone()));
+                    Value::Object(obj)
+                }
+                other => {
+                    let mut obj = serde_json::Map::new();
+                    obj.insert("account".to_string(), Value::String(account.clone()));
+                    obj.insert("value".to_string(), other);
+                    Value::Object(obj)
+                }
+            };
+            merged.push(tagged);
+        }
+    }
+    let raw = serde_json::to_string(&Results {
+        results: vec![serde_json::json!({ "events": merged })],
+    })?;
+    Ok(QueryResults { raw })
+}
+
+async fn process_matches(matches: ArgMatches<'_>) -> Result<(), Error> {
+    if let Some(cache) = matches.subcommand_matches("cache") {
+        if let Some(_clear) = cache.subcommand_matches("clear") {
+            QueryCache::open()?.clear()?;
+            println!("Cache cleared.");
+        }
+        return Ok(());
+    }
+    if let Some(run) = matches.subcommand_matches("run") {
+        let wants_fanout = matches.is_present("all_accounts")
+            || matches.value_of("account").map_or(false, |a| a.contains(','));
+        if wants_fanout {
+            if run.is_present("follow") {
+                return Err(err_msg(
+                    "--follow cannot be combined with --all-accounts or multiple accounts.",
+                ));
+            }
+            let nrql = run.value_of("nrql").unwrap();
+            let config = load_config()?;
+            let account_keys = account_keys_for_fanout(&matches, &config)?.unwrap();
+            let format = Format::from_args(&run)?;
+            run_query_across_accounts(&matches, &config, &account_keys, nrql)
+                .await?
+                .print(format)?;
+            return Ok(());
+        }
+    }
     let connection = Connection::from_args(&matches)?;
+    if let Some(serve_matches) = matches.subcommand_matches("serve") {
+        return serve(connection, &serve_matches).await;
+    }
+    if let Some(batch) = matches.subcommand_matches("batch") {
+        let file = batch.value_of("file").unwrap();
+        let concurrency: usize = batch
+            .value_of("concurrency")
+            .unwrap_or("4")
+            .parse()
+            .map_err(|_| err_msg("Invalid --concurrency value!"))?;
+        let queries = read_batch_file(file)?;
+        let format = Format::from_args(&batch)?;
+        let results = run_batch(&connection, queries, concurrency).await;
+        let mut any_failed = false;
+        for (index, query, result) in results {
+            println!("--- [{}] {}", index, query);
+            match result.and_then(|r| r.print(format.clone())) {
+                Ok(()) => {}
+                Err(e) => {
+                    any_failed = true;
+                    eprintln!("{}", e);
+                }
+            }
+        }
+        if any_failed {
+            return Err(err_msg("One or more batch queries failed."));
+        }
+        return Ok(());
+    }
     if let Some(run) = matches.subcommand_matches("run") {
         let nrql = run.value_of("nrql").unwrap();
-        connection.run_query(nrql)?.print(Format::from_args(&run))?;
+        if run.is_present("follow") {
+            return follow_query(connection, &matches, nrql, &run).await;
+        }
+        connection
+            .run_query(nrql)
+            .await?
+            .print(Format::from_args(&run)?)?;
     }
     if let Some(types) = matches.subcommand_matches("types") {
         connection
-            .run_query("show event types")?
-            .print(Format::from_args(&types))?;
+            .run_query("show event types")
+            .await?
+            .print(Format::from_args(&types)?)?;
     }
     if let Some(attrs) = matches.subcommand_matches("attrs") {
         connection
@@ -245,8 +889,9 @@ fn process_matches(matches: ArgMatches) -> Result<(), Error> {
                     attrs.value_of("type").unwrap()
                 )
                 .as_str(),
-            )?
-            .print(Format::from_args(&attrs))?;
+            )
+            .await?
+            .print(Format::from_args(&attrs)?)?;
     }
     if let Some(complete) = matches.subcommand_matches("complete") {
         let table = complete.value_of("type").unwrap();
@@ -258,8 +903,9 @@ fn process_matches(matches: ArgMatches) -> Result<(), Error> {
         query.push_str(" since 1 week ago");
 
         connection
-            .run_query(query.as_str())?
-            .print(Format::from_args(&complete))?;
+            .run_query(query.as_str())
+            .await?
+            .print(Format::from_args(&complete)?)?;
     }
     Ok(())
 }
@@ -290,6 +936,18 @@ impl FormattingFlags for App<'_, '_> {
                 .long("table")
                 .help("Format output as table (default)"),
         )
+        .arg(
+            Arg::with_name("template")
+                .long("template")
+                .takes_value(true)
+                .help("Render each result row through the Handlebars template in this file"),
+        )
+        .arg(
+            Arg::with_name("template_inline")
+                .long("template-inline")
+                .takes_value(true)
+                .help("Render each result row through this Handlebars template string"),
+        )
     }
 }
 
@@ -304,7 +962,12 @@ fn main() {
                 .long("account")
                 .short("a")
                 .takes_value(true)
-                .help("Account Config Key"),
+                .help("Account Config Key, or a comma-separated list to fan a `run` query out across"),
+        )
+        .arg(
+            Arg::with_name("all_accounts")
+                .long("all-accounts")
+                .help("Run a `run` query against every configured account and merge the results"),
         )
         .arg(
             Arg::with_name("account_id")
@@ -320,10 +983,45 @@ fn main() {
                 .takes_value(true)
                 .help("API Key"),
         )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .short("u")
+                .takes_value(true)
+                .help("Insights API URL"),
+        )
+        .arg(
+            Arg::with_name("cache_ttl")
+                .long("cache-ttl")
+                .takes_value(true)
+                .help("Cache query results for this long, e.g. 5m, 1h (default: no caching)"),
+        )
+        .arg(
+            Arg::with_name("no_cache")
+                .long("no-cache")
+                .help("Bypass the result cache even when --cache-ttl is set"),
+        )
         .subcommand(
             SubCommand::with_name("run")
                 .about("Run an Insights query")
                 .arg(Arg::with_name("nrql").help("The NRQL to run"))
+                .arg(
+                    Arg::with_name("follow")
+                        .long("follow")
+                        .help("Re-run the query on an interval, refreshing the terminal"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .help("Seconds between re-runs when following (default 5)"),
+                )
+                .arg(
+                    Arg::with_name("count")
+                        .long("count")
+                        .takes_value(true)
+                        .help("Stop after N iterations when following"),
+                )
                 .add_formatting_flags(),
         )
         .subcommand(
@@ -341,6 +1039,38 @@ fn main() {
                 )
                 .add_formatting_flags(),
         )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serves NRQL queries over a local REST endpoint")
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .short("p")
+                        .takes_value(true)
+                        .help("Port to listen on (default 8080)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Manages the on-disk query result cache")
+                .subcommand(SubCommand::with_name("clear").about("Clears all cached results")),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("Runs every NRQL query in a file concurrently")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("File containing one NRQL query per line"),
+                )
+                .arg(
+                    Arg::with_name("concurrency")
+                        .long("concurrency")
+                        .takes_value(true)
+                        .help("Maximum number of queries to run at once (default 4)"),
+                )
+                .add_formatting_flags(),
+        )
         .subcommand(
             SubCommand::with_name("complete")
                 .about("Returns a list of valid completions")
@@ -358,7 +1088,8 @@ fn main() {
                 .add_formatting_flags(),
         )
         .get_matches();
-    match process_matches(matches) {
+    let mut rt = tokio::runtime::Runtime::new().expect("Unable to start async runtime!");
+    match rt.block_on(process_matches(matches)) {
         Ok(()) => std::process::exit(0),
         Err(e) => {
             eprintln!("{}", e);